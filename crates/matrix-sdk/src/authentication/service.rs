@@ -14,8 +14,12 @@
 
 //! High-level authentication APIs.
 
-use std::{error::Error, fmt::Debug, sync::RwLock};
+use std::{collections::HashMap, error::Error, fmt::Debug, sync::RwLock, time::Duration};
 
+// PKCE (RFC 7636) needs a CSPRNG, a SHA-256 digest, and URL-safe base64 encoding; `base64`,
+// `rand`, and `sha2` must be listed as direct dependencies of this crate's manifest.
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use rand::{distributions::Alphanumeric, Rng};
 use ruma::{
     api::{
         client::session::get_login_types,
@@ -23,10 +27,221 @@ use ruma::{
     },
     IdParseError,
 };
+use sha2::{Digest, Sha256};
 use url::Url;
 
 use crate::{sanitize_server_name, Client, ClientBuildError, ClientBuilder, HttpError, ServerName};
 
+/// The unreserved characters allowed in a PKCE code verifier, as defined by
+/// [RFC 7636 §4.1](https://datatracker.ietf.org/doc/html/rfc7636#section-4.1).
+#[cfg(feature = "experimental-oidc")]
+const PKCE_VERIFIER_CHARS: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
+
+/// The length of the code verifiers we generate. Must be between 43 and 128
+/// characters per RFC 7636; we use the maximum to make brute-forcing as hard
+/// as possible.
+#[cfg(feature = "experimental-oidc")]
+const PKCE_VERIFIER_LENGTH: usize = 128;
+
+/// The method used to derive a PKCE `code_challenge` from its `code_verifier`,
+/// as defined by [RFC 7636 §4.2](https://datatracker.ietf.org/doc/html/rfc7636#section-4.2).
+#[cfg(feature = "experimental-oidc")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CodeChallengeMethod {
+    /// `S256`: `code_challenge = BASE64URL-ENCODE(SHA256(ASCII(code_verifier)))`.
+    ///
+    /// Used whenever the authorization server advertises support for it.
+    S256,
+    /// `plain`: `code_challenge = code_verifier`.
+    ///
+    /// Only used as a fallback when the server doesn't advertise any other
+    /// method.
+    Plain,
+}
+
+#[cfg(feature = "experimental-oidc")]
+impl CodeChallengeMethod {
+    /// Picks the strongest method supported by the server, defaulting to
+    /// [`CodeChallengeMethod::S256`] when the server's supported methods
+    /// aren't known yet. `code_challenge_methods_supported` is optional in
+    /// the server metadata, so an empty list is treated the same as a
+    /// missing one rather than being read as "nothing is supported".
+    fn choose(methods_supported: Option<&[String]>) -> Self {
+        match methods_supported.filter(|methods| !methods.is_empty()) {
+            Some(methods) if methods.iter().any(|m| m == "S256") => Self::S256,
+            Some(_) => Self::Plain,
+            None => Self::S256,
+        }
+    }
+
+    /// The value to send as `code_challenge_method`.
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::S256 => "S256",
+            Self::Plain => "plain",
+        }
+    }
+}
+
+/// The PKCE parameters generated for a single OIDC authorization attempt.
+#[cfg(feature = "experimental-oidc")]
+#[derive(Clone)]
+struct PkceCodeChallenge {
+    /// The random verifier that must be sent during the token exchange.
+    verifier: String,
+    /// The challenge derived from `verifier`, sent in the authorization
+    /// request.
+    challenge: String,
+    /// The method used to derive `challenge` from `verifier`.
+    method: CodeChallengeMethod,
+}
+
+#[cfg(feature = "experimental-oidc")]
+impl PkceCodeChallenge {
+    /// Generates a new, random code verifier and derives its challenge using
+    /// the given method.
+    fn new(method: CodeChallengeMethod) -> Self {
+        let verifier = generate_pkce_verifier();
+        let challenge = match method {
+            CodeChallengeMethod::S256 => {
+                URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+            }
+            CodeChallengeMethod::Plain => verifier.clone(),
+        };
+
+        Self { verifier, challenge, method }
+    }
+}
+
+/// Generates a cryptographically random PKCE code verifier, drawn from the
+/// unreserved character set allowed by RFC 7636.
+#[cfg(feature = "experimental-oidc")]
+fn generate_pkce_verifier() -> String {
+    let mut rng = rand::thread_rng();
+    (0..PKCE_VERIFIER_LENGTH)
+        .map(|_| PKCE_VERIFIER_CHARS[rng.gen_range(0..PKCE_VERIFIER_CHARS.len())] as char)
+        .collect()
+}
+
+/// Generates an opaque, random `state` value used to correlate an
+/// authorization response with the request that started it.
+#[cfg(feature = "experimental-oidc")]
+fn generate_state() -> String {
+    rand::thread_rng().sample_iter(Alphanumeric).map(char::from).take(32).collect()
+}
+
+/// The data returned after starting an OIDC authorization attempt. The
+/// embedding application should open [`Self::url`] in a web view and, once
+/// the authorization server redirects back with a `code`, pass it together
+/// with [`Self::state`] to [`AuthenticationService::complete_oidc_login`].
+#[cfg(feature = "experimental-oidc")]
+#[derive(Debug)]
+pub struct OidcAuthorizationData {
+    /// The URL that should be opened to let the user complete the
+    /// authorization.
+    pub url: Url,
+    /// The random value used to correlate this attempt with its callback, and
+    /// to guard against CSRF.
+    pub state: String,
+}
+
+/// The name of the well-known path an OAuth 2.0 Authorization Server
+/// Metadata document is published at, relative to the issuer, as defined by
+/// [RFC 8414](https://datatracker.ietf.org/doc/html/rfc8414) and MSC2965.
+#[cfg(feature = "experimental-oidc")]
+const OAUTH_AUTHORIZATION_SERVER_WELL_KNOWN_PATH: &str = ".well-known/oauth-authorization-server";
+
+/// The OAuth 2.0 Authorization Server Metadata document discovered for a
+/// homeserver's issuer, as defined by RFC 8414 and MSC2965.
+///
+/// This lets callers decide up front whether dynamic client registration,
+/// refresh tokens, or `S256` PKCE are available, without having to attempt
+/// the corresponding request first.
+#[cfg(feature = "experimental-oidc")]
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AuthorizationServerMetadata {
+    /// The authorization server's issuer identifier.
+    pub issuer: String,
+    /// The URL of the authorization endpoint.
+    pub authorization_endpoint: Url,
+    /// The URL of the token endpoint.
+    pub token_endpoint: Url,
+    /// The URL of the token introspection endpoint, if the server supports
+    /// it.
+    pub introspection_endpoint: Option<Url>,
+    /// The client authentication methods the introspection endpoint
+    /// supports, such as `client_secret_basic` or `tls_client_auth`.
+    #[serde(default)]
+    pub introspection_endpoint_auth_methods_supported: Vec<String>,
+    /// The URL of the token revocation endpoint, if the server supports it.
+    pub revocation_endpoint: Option<Url>,
+    /// The URL of the dynamic client registration endpoint, if the server
+    /// supports it.
+    pub registration_endpoint: Option<Url>,
+    /// The URL of the server's JSON Web Key Set document, if published.
+    pub jwks_uri: Option<Url>,
+    /// The OAuth 2.0 grant types the server supports.
+    #[serde(default)]
+    pub grant_types_supported: Vec<String>,
+    /// The OAuth 2.0 `response_type` values the server supports.
+    #[serde(default)]
+    pub response_types_supported: Vec<String>,
+    /// The PKCE `code_challenge_method` values the server supports.
+    #[serde(default)]
+    pub code_challenge_methods_supported: Vec<String>,
+}
+
+/// Fetches and parses the OAuth 2.0 Authorization Server Metadata document
+/// published by `issuer`, using `http_client` so any TLS client
+/// authentication material configured via [`AuthenticationService::set_tls_config`]
+/// is presented if the issuer requires it. Bounded by `timeout`, since this
+/// is a single request made outside the (separately-bounded) homeserver
+/// discovery sequence.
+#[cfg(feature = "experimental-oidc")]
+async fn fetch_authorization_server_metadata(
+    http_client: &reqwest::Client,
+    issuer: &str,
+    timeout: Duration,
+) -> Result<AuthorizationServerMetadata, AuthenticationError> {
+    let metadata_url = oauth_authorization_server_metadata_url(issuer)?;
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    let response = tokio::time::timeout_at(deadline, http_client.get(metadata_url).send())
+        .await
+        .map_err(|_| AuthenticationError::Timeout)?
+        .map_err(|e| AuthenticationError::Generic(Box::new(e)))?;
+
+    tokio::time::timeout_at(deadline, response.json())
+        .await
+        .map_err(|_| AuthenticationError::Timeout)?
+        .map_err(AuthenticationError::InvalidOidcMetadata)
+}
+
+/// Builds the URL the OAuth 2.0 Authorization Server Metadata document is
+/// expected to be published at for `issuer`, as defined by
+/// [RFC 8414 §3.1](https://datatracker.ietf.org/doc/html/rfc8414#section-3.1).
+///
+/// The well-known suffix is inserted between the authority and the issuer's
+/// path component, not simply appended to the full issuer URL: an issuer of
+/// `https://example.com/tenant1` resolves to
+/// `https://example.com/.well-known/oauth-authorization-server/tenant1`.
+#[cfg(feature = "experimental-oidc")]
+fn oauth_authorization_server_metadata_url(issuer: &str) -> Result<Url, AuthenticationError> {
+    let mut metadata_url =
+        Url::parse(issuer).map_err(|e| AuthenticationError::Generic(Box::new(e)))?;
+
+    let issuer_path = metadata_url.path().trim_matches('/');
+    let well_known_path = if issuer_path.is_empty() {
+        format!("/{OAUTH_AUTHORIZATION_SERVER_WELL_KNOWN_PATH}")
+    } else {
+        format!("/{OAUTH_AUTHORIZATION_SERVER_WELL_KNOWN_PATH}/{issuer_path}")
+    };
+    metadata_url.set_path(&well_known_path);
+
+    Ok(metadata_url)
+}
+
 /// A high-level service for authenticating a user with a homeserver.
 #[derive(Debug)]
 pub struct AuthenticationService {
@@ -41,8 +256,122 @@ pub struct AuthenticationService {
     /// homeserver that hasn't yet been configured with one.
     #[cfg(feature = "experimental-sliding-sync")]
     pub custom_sliding_sync_proxy: RwLock<Option<String>>,
+    /// The PKCE code verifiers of in-flight OIDC authorization attempts,
+    /// keyed by the `state` value generated for each attempt.
+    #[cfg(feature = "experimental-oidc")]
+    pending_pkce_verifiers: RwLock<HashMap<String, String>>,
+    /// The timeout applied to each single request made while configuring a
+    /// homeserver outside the discovery sequence: the check that a homeserver
+    /// URL responds, the login types request and the OIDC metadata fetch.
+    /// Requests made during server name discovery are bounded only by
+    /// `discovery_timeout`. Defaults to [`DEFAULT_REQUEST_TIMEOUT`].
+    request_timeout: RwLock<Duration>,
+    /// The timeout applied to the full homeserver discovery sequence, which
+    /// may chain several HTTP round-trips. Defaults to
+    /// [`DEFAULT_DISCOVERY_TIMEOUT`].
+    discovery_timeout: RwLock<Duration>,
+    /// TLS client authentication material installed on every request the
+    /// service's client makes, for homeservers or authorization servers that
+    /// require `tls_client_auth`.
+    tls_config: RwLock<Option<ClientTlsConfig>>,
+}
+
+/// TLS client authentication material installed on every request the
+/// service's [`Client`] makes, for use with homeservers or authorization
+/// servers that require `tls_client_auth` or `self_signed_tls_client_auth`
+/// (see [`ClientAuthMethod`]).
+#[derive(Clone, Default)]
+pub struct ClientTlsConfig {
+    identity: Option<reqwest::Identity>,
+    root_certificates: Vec<reqwest::Certificate>,
+}
+
+impl Debug for ClientTlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientTlsConfig")
+            .field("has_identity", &self.identity.is_some())
+            .field("root_certificate_count", &self.root_certificates.len())
+            .finish()
+    }
+}
+
+impl ClientTlsConfig {
+    /// Creates an empty configuration with no client certificate or custom CA
+    /// bundle installed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Installs a client certificate and private key, in PEM format, to
+    /// present to servers that require `tls_client_auth`.
+    pub fn with_identity_from_pem(mut self, pem: &[u8]) -> Result<Self, AuthenticationError> {
+        self.identity = Some(
+            reqwest::Identity::from_pem(pem)
+                .map_err(AuthenticationError::InvalidClientCertificate)?,
+        );
+        Ok(self)
+    }
+
+    /// Installs a client certificate and private key, in PKCS#12 DER format
+    /// protected by `password`, to present to servers that require
+    /// `tls_client_auth`.
+    pub fn with_identity_from_pkcs12_der(
+        mut self,
+        der: &[u8],
+        password: &str,
+    ) -> Result<Self, AuthenticationError> {
+        self.identity = Some(
+            reqwest::Identity::from_pkcs12_der(der, password)
+                .map_err(AuthenticationError::InvalidClientCertificate)?,
+        );
+        Ok(self)
+    }
+
+    /// Reads a client certificate and private key, in PEM format, from the
+    /// file at `path`.
+    pub fn with_identity_from_pem_file(
+        self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, AuthenticationError> {
+        let pem =
+            std::fs::read(path).map_err(|e| AuthenticationError::Generic(Box::new(e)))?;
+        self.with_identity_from_pem(&pem)
+    }
+
+    /// Trusts an additional root certificate, in PEM format, so that
+    /// self-signed server chains can be trusted without disabling
+    /// verification entirely.
+    pub fn with_root_certificate_from_pem(mut self, pem: &[u8]) -> Result<Self, AuthenticationError> {
+        let certificate = reqwest::Certificate::from_pem(pem)
+            .map_err(AuthenticationError::InvalidClientCertificate)?;
+        self.root_certificates.push(certificate);
+        Ok(self)
+    }
+
+    /// Reads an additional root certificate, in PEM format, from the file at
+    /// `path`, so that self-signed server chains can be trusted without
+    /// disabling verification entirely.
+    pub fn with_root_certificate_from_pem_file(
+        self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, AuthenticationError> {
+        let pem =
+            std::fs::read(path).map_err(|e| AuthenticationError::Generic(Box::new(e)))?;
+        self.with_root_certificate_from_pem(&pem)
+    }
 }
 
+/// The default timeout applied to a single request made while configuring a
+/// homeserver, unless overridden with
+/// [`AuthenticationService::set_request_timeout`].
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The default timeout applied to the full homeserver discovery sequence,
+/// unless overridden with [`AuthenticationService::set_discovery_timeout`].
+/// Longer than [`DEFAULT_REQUEST_TIMEOUT`] since auto-discovery can
+/// legitimately chain several HTTP round-trips.
+const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Errors related to authentication through the `AuthenticationService`.
 #[derive(Debug, thiserror::Error)]
 pub enum AuthenticationError {
@@ -71,9 +400,40 @@ pub enum AuthenticationError {
     /// An error occurred whilst trying to use the supplied base path.
     #[error("Failed to use the supplied base path.")]
     InvalidBasePath,
+    /// A request, or the full homeserver discovery sequence, didn't complete
+    /// within its configured timeout.
+    #[error("The request timed out.")]
+    Timeout,
+    /// The supplied client certificate, private key, or root certificate
+    /// couldn't be parsed.
+    #[error("Failed to parse the supplied TLS certificate: {0}")]
+    InvalidClientCertificate(reqwest::Error),
     /// An unknown error occurred.
     #[error("An error occurred: {0}")]
     Generic(Box<dyn Error>),
+    /// [`AuthenticationService::complete_oidc_login`] was called with a
+    /// `state` that doesn't match an in-flight authorization attempt. It may
+    /// have already been completed, or have never been started on this
+    /// service instance.
+    #[cfg(feature = "experimental-oidc")]
+    #[error("No OIDC authorization attempt is in progress for the given state.")]
+    OidcAuthorizationNotInProgress,
+    /// The discovered OAuth 2.0 Authorization Server Metadata document is
+    /// malformed.
+    #[cfg(feature = "experimental-oidc")]
+    #[error("Failed to parse the OAuth 2.0 Authorization Server Metadata: {0}")]
+    InvalidOidcMetadata(reqwest::Error),
+    /// The response to a token introspection request is malformed.
+    #[cfg(feature = "experimental-oidc")]
+    #[error("Failed to parse the token introspection response: {0}")]
+    InvalidIntrospectionResponse(reqwest::Error),
+    /// The homeserver doesn't support OIDC login, or its authorization server
+    /// metadata hasn't been discovered yet. A successful call to
+    /// `configure_homeserver` against an OIDC-capable homeserver must be made
+    /// first.
+    #[cfg(feature = "experimental-oidc")]
+    #[error("The homeserver doesn't support OIDC login, or its metadata hasn't been discovered.")]
+    OidcNotSupported,
 }
 
 /// Details about a homeserver's login capabilities.
@@ -84,6 +444,18 @@ pub struct HomeserverLoginDetails {
     /// Whether the homeserver supports login using OIDC as defined by MSC3861.
     #[cfg(feature = "experimental-oidc")]
     pub supports_oidc_login: bool,
+    /// The homeserver's OAuth 2.0 Authorization Server Metadata, if it
+    /// supports OIDC login and the metadata document could be fetched and
+    /// parsed.
+    #[cfg(feature = "experimental-oidc")]
+    pub oidc_metadata: Option<AuthorizationServerMetadata>,
+    /// If the homeserver advertised OIDC support but its Authorization
+    /// Server Metadata document couldn't be fetched or parsed, the resulting
+    /// error message is recorded here rather than failing
+    /// `configure_homeserver` outright for what is otherwise a usable
+    /// homeserver. `oidc_metadata` is `None` whenever this is `Some`.
+    #[cfg(feature = "experimental-oidc")]
+    pub oidc_metadata_error: Option<String>,
     /// Whether the homeserver supports the password login flow.
     pub supports_password_login: bool,
 }
@@ -100,9 +472,27 @@ impl AuthenticationService {
             homeserver_details: RwLock::new(None),
             #[cfg(feature = "experimental-sliding-sync")]
             custom_sliding_sync_proxy: RwLock::new(custom_sliding_sync_proxy),
+            #[cfg(feature = "experimental-oidc")]
+            pending_pkce_verifiers: RwLock::new(HashMap::new()),
+            request_timeout: RwLock::new(DEFAULT_REQUEST_TIMEOUT),
+            discovery_timeout: RwLock::new(DEFAULT_DISCOVERY_TIMEOUT),
+            tls_config: RwLock::new(None),
         }
     }
 
+    /// Installs TLS client authentication material to present on every
+    /// request made by clients this service builds from now on. Pass `None`
+    /// to stop presenting a client certificate or trusting extra root
+    /// certificates.
+    ///
+    /// A [`Client`] that's already been built keeps the material it was built
+    /// with, so this only affects its requests for homeservers configured
+    /// after this call. OIDC metadata fetches and token introspection requests
+    /// use the new material from the next request on.
+    pub fn set_tls_config(&self, tls_config: Option<ClientTlsConfig>) {
+        *self.tls_config.write().unwrap() = tls_config;
+    }
+
     /// Returns the homeserver details for the currently configured homeserver,
     /// or `None` if a successful call to `configure_homeserver` is yet to be
     /// made.
@@ -110,6 +500,21 @@ impl AuthenticationService {
         self.homeserver_details.read().unwrap().clone()
     }
 
+    /// Overrides the timeout applied to each single request made while
+    /// configuring a homeserver outside the discovery sequence. Defaults to
+    /// [`DEFAULT_REQUEST_TIMEOUT`].
+    pub fn set_request_timeout(&self, timeout: Duration) {
+        *self.request_timeout.write().unwrap() = timeout;
+    }
+
+    /// Overrides the timeout applied to the full homeserver discovery
+    /// sequence. Since auto-discovery can legitimately chain several HTTP
+    /// round-trips, this should generally be longer than the request
+    /// timeout. Defaults to [`DEFAULT_DISCOVERY_TIMEOUT`].
+    pub fn set_discovery_timeout(&self, timeout: Duration) {
+        *self.discovery_timeout.write().unwrap() = timeout;
+    }
+
     /// Updates the service to authenticate with the homeserver for the
     /// specified address.
     pub async fn configure_homeserver(
@@ -133,6 +538,178 @@ impl AuthenticationService {
 
         Ok(())
     }
+
+    /// Starts an OIDC authorization-code flow as defined by MSC3861, using
+    /// PKCE (RFC 7636) to protect public clients against authorization-code
+    /// interception.
+    ///
+    /// Returns the URL that the embedding application should open in a web
+    /// view, together with the `state` that must be passed back into
+    /// [`Self::complete_oidc_login`] alongside the `code` the authorization
+    /// server redirects back with.
+    ///
+    /// The authorization endpoint and the PKCE method are taken from the
+    /// homeserver's discovered OAuth 2.0 Authorization Server Metadata, so a
+    /// successful call to `configure_homeserver` must be made first.
+    /// `client_id` must currently be supplied by the caller, as dynamic
+    /// client registration isn't implemented yet.
+    #[cfg(feature = "experimental-oidc")]
+    pub fn oidc_authorization_url(
+        &self,
+        client_id: &str,
+        redirect_uri: &Url,
+        scopes: &[String],
+    ) -> Result<OidcAuthorizationData, AuthenticationError> {
+        let homeserver_details = self.homeserver_details.read().unwrap();
+        let metadata = homeserver_details
+            .as_ref()
+            .and_then(|details| details.oidc_metadata.as_ref())
+            .ok_or(AuthenticationError::OidcNotSupported)?;
+
+        let state = generate_state();
+        let method = CodeChallengeMethod::choose(Some(&metadata.code_challenge_methods_supported));
+        let pkce = PkceCodeChallenge::new(method);
+
+        let mut url = metadata.authorization_endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", client_id)
+            .append_pair("redirect_uri", redirect_uri.as_str())
+            .append_pair("scope", &scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("code_challenge", &pkce.challenge)
+            .append_pair("code_challenge_method", pkce.method.as_str());
+
+        self.pending_pkce_verifiers.write().unwrap().insert(state.clone(), pkce.verifier);
+
+        Ok(OidcAuthorizationData { url, state })
+    }
+
+    /// Completes an OIDC authorization-code flow previously started with
+    /// [`Self::oidc_authorization_url`], exchanging `code` for a session.
+    ///
+    /// The `code_verifier` stashed for `state` is sent alongside `code` so
+    /// the authorization server can recompute and compare the PKCE
+    /// challenge, per RFC 7636.
+    #[cfg(feature = "experimental-oidc")]
+    pub async fn complete_oidc_login(&self, state: &str, code: &str) -> Result<(), AuthenticationError> {
+        let code_verifier = self
+            .pending_pkce_verifiers
+            .write()
+            .unwrap()
+            .remove(state)
+            .ok_or(AuthenticationError::OidcAuthorizationNotInProgress)?;
+
+        let client = self.client.read().unwrap().clone().ok_or(AuthenticationError::ClientMissing)?;
+
+        client
+            .oidc()
+            .finish_authorization(code, &code_verifier)
+            .await
+            .map_err(|e| AuthenticationError::Generic(Box::new(e)))
+    }
+
+    /// Asks the homeserver's authorization server whether `token` is still
+    /// active, as defined by [RFC 7662](https://datatracker.ietf.org/doc/html/rfc7662).
+    ///
+    /// This lets long-running sessions proactively detect revocation instead
+    /// of only finding out the next time the token is used and rejected.
+    /// `auth_method` selects how the client authenticates itself to the
+    /// introspection endpoint; it should be picked from what the homeserver's
+    /// [`AuthorizationServerMetadata`] advertises as supported.
+    #[cfg(feature = "experimental-oidc")]
+    pub async fn introspect_token(
+        &self,
+        client_id: &str,
+        auth_method: &ClientAuthMethod,
+        token: &str,
+    ) -> Result<IntrospectionResponse, AuthenticationError> {
+        let homeserver_details = self.homeserver_details.read().unwrap();
+        let metadata = homeserver_details
+            .as_ref()
+            .and_then(|details| details.oidc_metadata.as_ref())
+            .ok_or(AuthenticationError::OidcNotSupported)?;
+        let introspection_endpoint = metadata
+            .introspection_endpoint
+            .clone()
+            .ok_or(AuthenticationError::OidcNotSupported)?;
+        drop(homeserver_details);
+
+        let mut params = vec![("token", token.to_owned())];
+
+        let mut request = self.http_client()?.post(introspection_endpoint);
+        request = match auth_method {
+            ClientAuthMethod::ClientSecretBasic { client_secret } => {
+                request.basic_auth(client_id, Some(client_secret))
+            }
+            ClientAuthMethod::ClientSecretPost { client_secret } => {
+                params.push(("client_id", client_id.to_owned()));
+                params.push(("client_secret", client_secret.clone()));
+                request
+            }
+            // The client is authenticated at the TLS layer by the certificate installed on
+            // the underlying client builder; only the client_id needs to be sent here.
+            ClientAuthMethod::TlsClientAuth | ClientAuthMethod::SelfSignedTlsClientAuth => {
+                params.push(("client_id", client_id.to_owned()));
+                request
+            }
+        };
+
+        let response = request
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| AuthenticationError::Generic(Box::new(e)))?;
+
+        response.json().await.map_err(AuthenticationError::InvalidIntrospectionResponse)
+    }
+}
+
+/// The method a client uses to authenticate itself to the authorization
+/// server's introspection (and other confidential-client) endpoints.
+///
+/// The available methods should be chosen from what the server advertises in
+/// its [`AuthorizationServerMetadata`]; not every server supports every
+/// method.
+#[cfg(feature = "experimental-oidc")]
+#[derive(Clone, Debug)]
+pub enum ClientAuthMethod {
+    /// `client_secret_basic`: the client ID and secret are sent as an HTTP
+    /// Basic `Authorization` header.
+    ClientSecretBasic {
+        /// The client's secret.
+        client_secret: String,
+    },
+    /// `client_secret_post`: the client ID and secret are sent in the
+    /// request body.
+    ClientSecretPost {
+        /// The client's secret.
+        client_secret: String,
+    },
+    /// `tls_client_auth`: the client authenticates using a client
+    /// certificate issued by a CA the server trusts, installed with
+    /// [`AuthenticationService::set_tls_config`].
+    TlsClientAuth,
+    /// `self_signed_tls_client_auth`: the client authenticates using a
+    /// self-signed client certificate whose public key the server has been
+    /// configured to trust out-of-band.
+    SelfSignedTlsClientAuth,
+}
+
+/// The response to a token introspection request, as defined by RFC 7662.
+#[cfg(feature = "experimental-oidc")]
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct IntrospectionResponse {
+    /// Whether the token is currently active.
+    pub active: bool,
+    /// The scopes associated with the token, if any.
+    pub scope: Option<String>,
+    /// The client the token was issued to, if known.
+    pub client_id: Option<String>,
+    /// The subject the token was issued for, if known.
+    pub sub: Option<String>,
+    /// The Unix timestamp at which the token expires, if known.
+    pub exp: Option<u64>,
 }
 
 impl AuthenticationService {
@@ -144,13 +721,20 @@ impl AuthenticationService {
         let mut build_error: AuthenticationError =
             AuthenticationError::Generic("Unknown error occurred.".into());
 
+        let discovery_timeout = *self.discovery_timeout.read().unwrap();
+
         // Attempt discovery as a server name first.
         let sanitize_result = sanitize_server_name(&server_name_or_homeserver_url);
         if let Ok(server_name) = sanitize_result.as_ref() {
             let insecure = server_name_or_homeserver_url.starts_with("http://");
-            match self.build_client_for_server_name(server_name, insecure).await {
-                Ok(client) => return Ok(client),
-                Err(e) => {
+            match tokio::time::timeout(
+                discovery_timeout,
+                self.build_client_for_server_name(server_name, insecure),
+            )
+            .await
+            {
+                Ok(Ok(client)) => return Ok(client),
+                Ok(Err(e)) => {
                     build_error = match e {
                         ClientBuildError::Http(HttpError::Reqwest(_)) => {
                             AuthenticationError::ServerNotFound
@@ -164,18 +748,30 @@ impl AuthenticationService {
                         _ => AuthenticationError::Generic(Box::new(e)),
                     }
                 }
+                // The whole discovery sequence (which may chain several HTTP round-trips)
+                // didn't complete within the budget; report this distinctly from the
+                // homeserver actively refusing the connection.
+                Err(_) => return Err(AuthenticationError::Timeout),
             };
         }
 
         // When discovery fails, or the input isn't a valid server name, fallback to
         // trying a homeserver URL if supplied.
         if let Ok(homeserver_url) = Url::parse(&server_name_or_homeserver_url) {
-            if let Some(client) = self.build_client_for_homeserver_url(homeserver_url).await {
-                return Ok(client);
+            match tokio::time::timeout(
+                discovery_timeout,
+                self.build_client_for_homeserver_url(homeserver_url),
+            )
+            .await
+            {
+                Ok(Some(client)) => return Ok(client),
+                Ok(None) => {
+                    // No need to worry about the error branch here as the server name
+                    // is preferred (to get a well-known file), so we'll return the
+                    // error from above instead.
+                }
+                Err(_) => return Err(AuthenticationError::Timeout),
             }
-            // No need to worry about the error branch here as the server name
-            // is preferred (to get a well-known file), so we'll return the
-            // error from above instead.
         };
 
         if let Err(sanitize_result) = sanitize_result {
@@ -185,6 +781,40 @@ impl AuthenticationService {
         }
     }
 
+    /// Builds a bare `reqwest` client carrying the configured TLS client
+    /// authentication material (see [`Self::set_tls_config`]), for the
+    /// handful of requests this service makes that fall outside the
+    /// `matrix-sdk` [`Client`] it builds: OIDC metadata discovery and token
+    /// introspection.
+    #[cfg(feature = "experimental-oidc")]
+    fn http_client(&self) -> Result<reqwest::Client, AuthenticationError> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(tls_config) = self.tls_config.read().unwrap().clone() {
+            if let Some(identity) = tls_config.identity {
+                builder = builder.identity(identity);
+            }
+            for root_certificate in tls_config.root_certificates {
+                builder = builder.add_root_certificate(root_certificate);
+            }
+        }
+
+        builder.build().map_err(AuthenticationError::InvalidClientCertificate)
+    }
+
+    /// Fetches and parses the OAuth 2.0 Authorization Server Metadata
+    /// document for `issuer`, using the service's configured TLS client
+    /// authentication material and request timeout.
+    #[cfg(feature = "experimental-oidc")]
+    async fn fetch_oidc_metadata(
+        &self,
+        issuer: &str,
+    ) -> Result<AuthorizationServerMetadata, AuthenticationError> {
+        let http_client = self.http_client()?;
+        let request_timeout = *self.request_timeout.read().unwrap();
+        fetch_authorization_server_metadata(&http_client, issuer, request_timeout).await
+    }
+
     /// A new client builder pre-configured with a user agent if specified.
     fn new_client_builder(&self) -> ClientBuilder {
         let mut builder = ClientBuilder::new();
@@ -193,6 +823,15 @@ impl AuthenticationService {
             builder = builder.user_agent(user_agent);
         }
 
+        if let Some(tls_config) = self.tls_config.read().unwrap().clone() {
+            if let Some(identity) = tls_config.identity {
+                builder = builder.identity(identity);
+            }
+            for root_certificate in tls_config.root_certificates {
+                builder = builder.add_root_certificate(root_certificate);
+            }
+        }
+
         builder
     }
 
@@ -224,10 +863,12 @@ impl AuthenticationService {
         let client = builder.build().await.ok()?;
 
         // Building should always succeed, so we need to check that a homeserver
-        // actually exists at the supplied URL.
-        match client.server_versions().await {
-            Ok(_) => Some(client),
-            Err(_) => None,
+        // actually exists at the supplied URL. This is a single request, so it gets
+        // its own, shorter budget rather than what's left of the discovery timeout.
+        let request_timeout = *self.request_timeout.read().unwrap();
+        match tokio::time::timeout(request_timeout, client.server_versions()).await {
+            Ok(Ok(_)) => Some(client),
+            Ok(Err(_)) | Err(_) => None,
         }
     }
 
@@ -237,12 +878,27 @@ impl AuthenticationService {
         client: &Client,
     ) -> Result<HomeserverLoginDetails, AuthenticationError> {
         #[cfg(feature = "experimental-oidc")]
-        let supports_oidc_login = client.oidc().authentication_server_info().is_some();
-        let login_types = client
-            .matrix_auth()
-            .get_login_types()
-            .await
-            .map_err(|e| AuthenticationError::Generic(Box::new(e)))?;
+        let (supports_oidc_login, oidc_metadata, oidc_metadata_error) =
+            match client.oidc().authentication_server_info() {
+                Some(info) => {
+                    // The homeserver has already told us it supports OIDC login via its
+                    // well-known file; don't let a flaky or unpublished metadata document
+                    // take down an otherwise-working homeserver configuration.
+                    match self.fetch_oidc_metadata(&info.issuer).await {
+                        Ok(metadata) => (true, Some(metadata), None),
+                        Err(e) => (true, None, Some(e.to_string())),
+                    }
+                }
+                None => (false, None, None),
+            };
+        let request_timeout = *self.request_timeout.read().unwrap();
+        let login_types = tokio::time::timeout(
+            request_timeout,
+            client.matrix_auth().get_login_types(),
+        )
+        .await
+        .map_err(|_| AuthenticationError::Timeout)?
+        .map_err(|e| AuthenticationError::Generic(Box::new(e)))?;
         let supports_password_login = login_types
             .flows
             .iter()
@@ -253,7 +909,66 @@ impl AuthenticationService {
             url,
             #[cfg(feature = "experimental-oidc")]
             supports_oidc_login,
+            #[cfg(feature = "experimental-oidc")]
+            oidc_metadata,
+            #[cfg(feature = "experimental-oidc")]
+            oidc_metadata_error,
             supports_password_login,
         })
     }
 }
+
+#[cfg(all(test, feature = "experimental-oidc"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_pkce_verifiers_have_the_expected_length_and_charset() {
+        for _ in 0..100 {
+            let verifier = generate_pkce_verifier();
+
+            assert_eq!(verifier.len(), PKCE_VERIFIER_LENGTH);
+            assert!(
+                verifier.bytes().all(|b| PKCE_VERIFIER_CHARS.contains(&b)),
+                "verifier contained a character outside the RFC 7636 unreserved set: {verifier}"
+            );
+        }
+    }
+
+    #[test]
+    fn code_challenge_method_defaults_to_s256_when_unknown() {
+        assert_eq!(CodeChallengeMethod::choose(None), CodeChallengeMethod::S256);
+    }
+
+    #[test]
+    fn code_challenge_method_defaults_to_s256_when_advertised_methods_are_empty() {
+        assert_eq!(CodeChallengeMethod::choose(Some(&[])), CodeChallengeMethod::S256);
+    }
+
+    #[test]
+    fn code_challenge_method_picks_s256_when_advertised() {
+        let methods = ["plain".to_owned(), "S256".to_owned()];
+        assert_eq!(CodeChallengeMethod::choose(Some(&methods)), CodeChallengeMethod::S256);
+    }
+
+    #[test]
+    fn code_challenge_method_falls_back_to_plain_when_s256_isnt_advertised() {
+        let methods = ["plain".to_owned()];
+        assert_eq!(CodeChallengeMethod::choose(Some(&methods)), CodeChallengeMethod::Plain);
+    }
+
+    #[test]
+    fn metadata_well_known_url_is_appended_for_an_issuer_without_a_path() {
+        let url = oauth_authorization_server_metadata_url("https://example.com").unwrap();
+        assert_eq!(url.as_str(), "https://example.com/.well-known/oauth-authorization-server");
+    }
+
+    #[test]
+    fn metadata_well_known_url_is_inserted_before_an_issuer_with_a_path() {
+        let url = oauth_authorization_server_metadata_url("https://example.com/tenant1").unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://example.com/.well-known/oauth-authorization-server/tenant1"
+        );
+    }
+}